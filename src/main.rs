@@ -1,6 +1,14 @@
 use std::io::BufRead;
 use std::cmp::{Ord, Ordering};
-use std::collections::{HashSet, BinaryHeap};
+use std::collections::{HashSet, BinaryHeap, VecDeque};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use itertools::Itertools;
 
 #[derive(Debug, PartialEq)]
 struct TrapsPuzzle {
@@ -56,57 +64,329 @@ fn naive_solve(puzzle: &TrapsPuzzle) -> usize {
     puzzle.dmg_from_skip_inds(&skip_inds)
 }
 
+// Above this, C(n, k) is too large to brute force
+const BRUTE_FORCE_COMBINATION_LIMIT: u128 = 50_000_000;
 
-fn parse_traps_puzzle<R>(input: &mut R) -> TrapsPuzzle
-    where R: BufRead
-{
-    let mut buf = String::new();
-    input.read_line(&mut buf).unwrap();
+// Computed incrementally so it never forms the full (possibly huge) value
+fn n_choose_k_exceeds(n: usize, k: usize, limit: u128) -> bool {
+    if k > n {
+        return false;
+    }
+    let k = k.min(n - k);
+
+    let mut c: u128 = 1;
+    for i in 0..k {
+        c = c * (n - i) as u128 / (i + 1) as u128;
+        if c > limit {
+            return true;
+        }
+    }
+
+    false
+}
+
+// Tries every one of the C(n, k) skip sets; a correctness oracle, not a real solver
+fn brute_force_solve(puzzle: &TrapsPuzzle) -> usize {
+    assert!(
+        !n_choose_k_exceeds(puzzle.base_dmgs.len(), puzzle.k, BRUTE_FORCE_COMBINATION_LIMIT),
+        "brute_force_solve: C({}, {}) is too large to brute force",
+        puzzle.base_dmgs.len(),
+        puzzle.k
+    );
+
+    (0..puzzle.base_dmgs.len()).combinations(puzzle.k)
+        .map(HashSet::from_iter)
+        .map( |skip_inds| puzzle.dmg_from_skip_inds(&skip_inds))
+        .min()
+        .unwrap()
+}
+
+// dp[s] = min damage after a prefix with exactly s traps skipped. O(n*k), O(k) space.
+fn exact_solve(puzzle: &TrapsPuzzle) -> usize {
+    let k = puzzle.k;
+    let mut dp = vec![usize::MAX; k + 1];
+    dp[0] = 0;
+
+    for &base in &puzzle.base_dmgs {
+        for s in (0..=k).rev() {
+            let keep = dp[s].checked_add(base).and_then(|v| v.checked_add(s));
+            let skip = if s > 0 { Some(dp[s - 1]) } else { None };
+            dp[s] = keep.into_iter().chain(skip).min().unwrap_or(usize::MAX);
+        }
+    }
+
+    dp[k]
+}
+
+const OPTIMAL_COUNT_MODULUS: u64 = 998244353;
+
+// Smaller damage wins outright; a tie adds the counts mod OPTIMAL_COUNT_MODULUS
+fn merge_optimal_counts(a: (usize, u64), b: (usize, u64)) -> (usize, u64) {
+    match a.0.cmp(&b.0) {
+        Ordering::Less => a,
+        Ordering::Greater => b,
+        Ordering::Equal => (a.0, (a.1 + b.1) % OPTIMAL_COUNT_MODULUS),
+    }
+}
+
+// Like exact_solve, but each dp[s] also carries the count of optimal skip-sets
+fn count_optimal_solve(puzzle: &TrapsPuzzle) -> (usize, u64) {
+    let k = puzzle.k;
+    let mut dp = vec![(usize::MAX, 0u64); k + 1];
+    dp[0] = (0, 1);
+
+    for &base in &puzzle.base_dmgs {
+        for s in (0..=k).rev() {
+            let keep = dp[s].0.checked_add(base)
+                .and_then(|v| v.checked_add(s))
+                .map(|dmg| (dmg, dp[s].1));
+            let skip = if s > 0 { Some(dp[s - 1]) } else { None };
+
+            dp[s] = match (keep, skip) {
+                (Some(a), Some(b)) => merge_optimal_counts(a, b),
+                (Some(a), None) => a,
+                (None, Some(b)) => b,
+                (None, None) => (usize::MAX, 0),
+            };
+        }
+    }
+
+    dp[k]
+}
+
+
+// Whitespace-delimited token reader; tokens may split across lines
+struct Scanner<R: BufRead> {
+    reader: R,
+    buf: VecDeque<String>,
+}
+
+impl<R: BufRead> Scanner<R> {
+    fn new(reader: R) -> Self {
+        Scanner { reader, buf: VecDeque::new() }
+    }
+
+    fn next<T: FromStr>(&mut self) -> T
+        where T::Err: std::fmt::Debug
+    {
+        while self.buf.is_empty() {
+            let mut line = String::new();
+            let bytes_read = self.reader.read_line(&mut line).unwrap();
+            if bytes_read == 0 {
+                panic!("Scanner: unexpected end of input");
+            }
+            self.buf.extend( line.split_whitespace().map(String::from) );
+        }
 
-    // Find out how many traps and jumps we have
-    let line_1_nums: Vec<usize> = buf.split(' ')
-        .map(|s| s.trim().parse().unwrap())
-        .collect();
-    
-    let mut puzzle = TrapsPuzzle {
-        base_dmgs: Vec::with_capacity( line_1_nums[0] ),
-        k: line_1_nums[1],
-    };
+        self.buf.pop_front().unwrap().parse().unwrap()
+    }
 
-    // Populate trap damages 
-    buf.clear();
-    input.read_line(&mut buf).unwrap();
-    for s in buf.split(' ') {
-        puzzle.base_dmgs.push( s.trim().parse().unwrap() );
+    fn next_n<T: FromStr>(&mut self, count: usize) -> Vec<T>
+        where T::Err: std::fmt::Debug
+    {
+        (0..count).map(|_| self.next()).collect()
     }
+}
+
+fn parse_traps_puzzle<R>(scanner: &mut Scanner<R>) -> TrapsPuzzle
+    where R: BufRead
+{
+    let n: usize = scanner.next();
+    let k: usize = scanner.next();
 
-    puzzle
+    TrapsPuzzle {
+        base_dmgs: scanner.next_n(n),
+        k,
+    }
 }
 
 
 fn parse_traps_puzzles<R>(input: &mut R) -> Vec<TrapsPuzzle>
     where R: BufRead
 {
+    let mut scanner = Scanner::new(input);
+
     // Find out how many puzzles we need to read
-    let mut buf = String::new();
-    input.read_line(&mut buf).unwrap();
-    let puzzle_count = buf.trim().parse().unwrap();
+    let puzzle_count = scanner.next();
 
     // Read that many puzzles into a Vec
     let mut puzzles = Vec::<TrapsPuzzle>::with_capacity(puzzle_count);
     for _ in 0..puzzle_count {
-        puzzles.push( parse_traps_puzzle(input) );
+        puzzles.push( parse_traps_puzzle(&mut scanner) );
     }
 
     puzzles
 }
 
 
+#[derive(Copy, Clone, ValueEnum)]
+enum Solver {
+    Naive,
+    Brute,
+    Exact,
+}
+
+impl Solver {
+    fn solve(self, puzzle: &TrapsPuzzle) -> usize {
+        match self {
+            Solver::Naive => naive_solve(puzzle),
+            Solver::Brute => brute_force_solve(puzzle),
+            Solver::Exact => exact_solve(puzzle),
+        }
+    }
+}
+
+#[derive(Parser)]
+#[command(author, version, about = "Solve, cross-check and benchmark traps puzzles")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Parse a batch of puzzles and print the minimum damage for each (default)
+    Solve {
+        /// Read puzzles from this file instead of stdin
+        #[arg(long)]
+        input: Option<PathBuf>,
+        /// Algorithm used to solve each puzzle
+        #[arg(long, value_enum, default_value_t = Solver::Naive)]
+        solver: Solver,
+    },
+    /// Cross-check naive_solve against exact_solve (and brute_force_solve,
+    /// where it's feasible) on every puzzle and report the first puzzle
+    /// where they disagree
+    Verify {
+        /// Read puzzles from this file instead of stdin
+        #[arg(long)]
+        input: Option<PathBuf>,
+    },
+    /// Parse once, then solve each puzzle `repeats` times and report timing
+    Bench {
+        /// Read puzzles from this file instead of stdin
+        #[arg(long)]
+        input: Option<PathBuf>,
+        /// Algorithm used to solve each puzzle
+        #[arg(long, value_enum, default_value_t = Solver::Naive)]
+        solver: Solver,
+        /// Number of times to solve each puzzle
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+        repeats: u64,
+    },
+    /// Print the minimum damage and the number of distinct skip-sets
+    /// (mod a prime) that achieve it, for every puzzle
+    Count {
+        /// Read puzzles from this file instead of stdin
+        #[arg(long)]
+        input: Option<PathBuf>,
+    },
+}
+
+/// Open `path`, or stdin if `None`, as a `BufRead`.
+fn open_input(path: &Option<PathBuf>) -> Box<dyn BufRead> {
+    match path {
+        Some(path) => Box::new(BufReader::new(File::open(path).unwrap())),
+        None => Box::new(BufReader::new(std::io::stdin())),
+    }
+}
+
+// Buffers `out` once (run_solve passes a locked stdout) rather than round-tripping through println! per puzzle
+fn solve_to<W: Write>(puzzles: &[TrapsPuzzle], solver: Solver, out: W) {
+    let mut out = BufWriter::new(out);
+    for puzzle in puzzles {
+        writeln!(out, "{}", solver.solve(puzzle)).unwrap();
+    }
+    out.flush().unwrap();
+}
+
+/// Exits with a clean error message, rather than letting `brute_force_solve`
+/// panic deep in the call stack, if `solver` is [`Solver::Brute`] and any
+/// puzzle's `C(n, k)` is too large to brute force.
+fn check_brute_force_feasible(puzzles: &[TrapsPuzzle], solver: Solver) {
+    if !matches!(solver, Solver::Brute) {
+        return;
+    }
+
+    for (i, puzzle) in puzzles.iter().enumerate() {
+        if n_choose_k_exceeds(puzzle.base_dmgs.len(), puzzle.k, BRUTE_FORCE_COMBINATION_LIMIT) {
+            eprintln!(
+                "error: puzzle {} has C({}, {}) too large to brute force",
+                i, puzzle.base_dmgs.len(), puzzle.k
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run_solve(input: Option<PathBuf>, solver: Solver) {
+    let puzzles = parse_traps_puzzles(&mut open_input(&input));
+    check_brute_force_feasible(&puzzles, solver);
+    solve_to(&puzzles, solver, std::io::stdout().lock());
+}
+
+fn run_verify(input: Option<PathBuf>) {
+    let puzzles = parse_traps_puzzles(&mut open_input(&input));
+    for (i, puzzle) in puzzles.iter().enumerate() {
+        let naive = naive_solve(puzzle);
+        let exact = exact_solve(puzzle);
+        if naive != exact {
+            println!(
+                "mismatch at puzzle {}: naive_solve={} exact_solve={} puzzle={:?}",
+                i, naive, exact, puzzle
+            );
+            return;
+        }
+
+        if !n_choose_k_exceeds(puzzle.base_dmgs.len(), puzzle.k, BRUTE_FORCE_COMBINATION_LIMIT) {
+            let brute = brute_force_solve(puzzle);
+            if naive != brute {
+                println!(
+                    "mismatch at puzzle {}: naive_solve={} brute_force_solve={} puzzle={:?}",
+                    i, naive, brute, puzzle
+                );
+                return;
+            }
+        }
+    }
+
+    println!("all {} puzzle(s) agree", puzzles.len());
+}
+
+fn run_bench(input: Option<PathBuf>, solver: Solver, repeats: u64) {
+    let puzzles = parse_traps_puzzles(&mut open_input(&input));
+    check_brute_force_feasible(&puzzles, solver);
+
+    let mut total = Duration::ZERO;
+    for (i, puzzle) in puzzles.iter().enumerate() {
+        let start = Instant::now();
+        for _ in 0..repeats {
+            solver.solve(puzzle);
+        }
+        let elapsed = Instant::now() - start;
+        total += elapsed;
+        println!("puzzle {}: {:?} total, {:?}/run", i, elapsed, elapsed / repeats as u32);
+    }
+
+    println!("all puzzles: {:?} total", total);
+}
+
+fn run_count(input: Option<PathBuf>) {
+    let puzzles = parse_traps_puzzles(&mut open_input(&input));
+    for puzzle in &puzzles {
+        let (min_damage, count) = count_optimal_solve(puzzle);
+        println!("{} {}", min_damage, count);
+    }
+}
+
 fn main() {
-    let puzzles = parse_traps_puzzles(&mut std::io::stdin().lock());
-    puzzles.iter()
-        .map(|puzzle| println!("{}", naive_solve(puzzle)))
-        .count();
+    let cli = Cli::parse();
+    match cli.command.unwrap_or(Command::Solve { input: None, solver: Solver::Naive }) {
+        Command::Solve { input, solver } => run_solve(input, solver),
+        Command::Verify { input } => run_verify(input),
+        Command::Bench { input, solver, repeats } => run_bench(input, solver, repeats),
+        Command::Count { input } => run_count(input),
+    }
 }
 
 
@@ -114,20 +394,40 @@ fn main() {
 mod tests {
     use super::*;
     use std::io::Cursor;
-    use itertools::Itertools;
     use rand::Rng;
-    use std::time::{Instant, Duration};
 
     #[test]
     fn parse_single_puzzle() {
-        let mut input = Cursor::new("3 2\n1 2 3\n");
-        let puzzle = parse_traps_puzzle(&mut input);
+        let input = Cursor::new("3 2\n1 2 3\n");
+        let mut scanner = Scanner::new(input);
+        let puzzle = parse_traps_puzzle(&mut scanner);
         assert_eq!(puzzle, TrapsPuzzle {
             base_dmgs: vec![1,2,3],
             k: 2
         });
     }
 
+    #[test]
+    fn parse_single_puzzle_damages_split_across_lines() {
+        let input = Cursor::new("5 2\n1 2\n3\n4 5\n");
+        let mut scanner = Scanner::new(input);
+        let puzzle = parse_traps_puzzle(&mut scanner);
+        assert_eq!(puzzle, TrapsPuzzle {
+            base_dmgs: vec![1,2,3,4,5],
+            k: 2
+        });
+    }
+
+    #[test]
+    fn parse_multiple_puzzles_damages_split_across_lines() {
+        let mut input = Cursor::new("2\n3 2\n1\n2 3\n1 1\n42");
+        let puzzles = parse_traps_puzzles(&mut input);
+        assert_eq!(puzzles, vec![
+            TrapsPuzzle {base_dmgs: vec![1,2,3], k: 2},
+            TrapsPuzzle {base_dmgs: vec![42],    k: 1}
+        ])
+    }
+
     #[test]
     fn parse_multiple_puzzles() {
         let mut input = Cursor::new("2\n3 2\n1 2 3\n1 1\n42");
@@ -138,14 +438,6 @@ mod tests {
         ])
     }
 
-    fn brute_force_solve(puzzle: &TrapsPuzzle) -> usize {
-        (0..puzzle.base_dmgs.len()).combinations(puzzle.k)
-            .map( |skip_inds| HashSet::from_iter(skip_inds.into_iter()) )
-            .map( |skip_inds| puzzle.dmg_from_skip_inds(&skip_inds))
-            .min()
-            .unwrap()
-    }
-
     #[test]
     fn brute_force_solver_works() {
         let puzzle = TrapsPuzzle {
@@ -176,10 +468,10 @@ mod tests {
         assert_eq!(brute_force_solve(&puzzle), naive_solve(&puzzle));
     }
 
-    fn naive_and_brute_force_agree(n: usize, k: usize, test_count: usize) {
+    fn naive_and_brute_force_agree(n: usize, k: usize, test_count: usize, check_brute: bool) {
         let mut puzzle = TrapsPuzzle {
             base_dmgs: vec![0; n],
-            k: k
+            k
         };
 
         let mut rng = rand::thread_rng();
@@ -188,7 +480,11 @@ mod tests {
             for i in 0..n {
                 puzzle.base_dmgs[i] = rng.gen_range(1..n+1);
             }
-            assert_eq!(brute_force_solve(&puzzle), naive_solve(&puzzle));
+            let naive = naive_solve(&puzzle);
+            if check_brute {
+                assert_eq!(brute_force_solve(&puzzle), naive);
+            }
+            assert_eq!(exact_solve(&puzzle), naive);
         }
     }
 
@@ -196,7 +492,54 @@ mod tests {
     fn naive_and_brute_agree_many() {
         for n in 1..10 {
             for k in 1..n {
-                naive_and_brute_force_agree(n, k, 1000);
+                naive_and_brute_force_agree(n, k, 1000, true);
+            }
+        }
+    }
+
+    #[test]
+    fn naive_and_exact_agree_at_sizes_beyond_brute_force() {
+        naive_and_brute_force_agree(500, 123, 20, false);
+        naive_and_brute_force_agree(500, 499, 20, false);
+    }
+
+    fn brute_force_count_optimal(puzzle: &TrapsPuzzle) -> (usize, u64) {
+        let min_damage = brute_force_solve(puzzle);
+        let count = (0..puzzle.base_dmgs.len()).combinations(puzzle.k)
+            .map(HashSet::from_iter)
+            .filter(|skip_inds| puzzle.dmg_from_skip_inds(skip_inds) == min_damage)
+            .count() as u64;
+
+        (min_damage, count % OPTIMAL_COUNT_MODULUS)
+    }
+
+    #[test]
+    fn count_optimal_matches_brute_force_single() {
+        let puzzle = TrapsPuzzle {
+            base_dmgs: vec![8,2,5,15,11,2,8],
+            k: 5
+        };
+
+        assert_eq!(count_optimal_solve(&puzzle), brute_force_count_optimal(&puzzle));
+    }
+
+    #[test]
+    fn count_optimal_matches_brute_force_many() {
+        let mut rng = rand::thread_rng();
+
+        for n in 1..10 {
+            for k in 1..n {
+                let mut puzzle = TrapsPuzzle {
+                    base_dmgs: vec![0; n],
+                    k
+                };
+
+                for _ in 0..200 {
+                    for i in 0..n {
+                        puzzle.base_dmgs[i] = rng.gen_range(1..n+1);
+                    }
+                    assert_eq!(count_optimal_solve(&puzzle), brute_force_count_optimal(&puzzle));
+                }
             }
         }
     }
@@ -208,7 +551,7 @@ mod tests {
 
         let mut puzzle = TrapsPuzzle {
             base_dmgs: vec![0; n],
-            k: k
+            k
         };
 
         let mut rng = rand::thread_rng();
@@ -222,4 +565,25 @@ mod tests {
         println!("Big puzzle solve time: {:?}", solve_duration);
         assert!(solve_duration < Duration::from_secs(1));
     }
+
+    #[test]
+    fn buffered_solve_pipeline_handles_many_puzzles_fast() {
+        let puzzle_count = 50000;
+
+        let mut input = format!("{}\n", puzzle_count);
+        for _ in 0..puzzle_count {
+            input.push_str("1 1\n42\n");
+        }
+        let puzzles = parse_traps_puzzles(&mut Cursor::new(input));
+
+        let mut output = Vec::new();
+        let start_time = Instant::now();
+        solve_to(&puzzles, Solver::Naive, &mut output);
+        let solve_duration = Instant::now() - start_time;
+        println!("Buffered solve time for {} puzzles: {:?}", puzzle_count, solve_duration);
+
+        let expected: String = "0\n".repeat(puzzle_count);
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+        assert!(solve_duration < Duration::from_secs(1));
+    }
 }